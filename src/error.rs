@@ -0,0 +1,82 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum PorterError {
+    Io(std::io::Error),
+    /// The image could not be decoded or encoded.
+    Image(image::ImageError),
+    BadThreshold {
+        lower: u16,
+        higher: u16,
+        boundary: u16,
+    },
+    /// The lower threshold is bigger than the higher one, for a property that can't wrap around.
+    ReversedThreshold {
+        lower: u16,
+        higher: u16,
+    },
+    UnsupportedFormat(String),
+    MaskSize {
+        mask: (u32, u32),
+        image: (u32, u32),
+    },
+    /// The path doesn't end with a file name that could be used for the output.
+    BadPath(PathBuf),
+}
+
+impl fmt::Display for PorterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PorterError::Io(e) => write!(f, "{}", e),
+            PorterError::Image(e) => write!(f, "{}", e),
+            PorterError::BadThreshold {
+                lower,
+                higher,
+                boundary,
+            } => write!(
+                f,
+                "bad threshold range {}..={} (thresholds go from 0 to {})",
+                lower, higher, boundary
+            ),
+            PorterError::ReversedThreshold { lower, higher } => write!(
+                f,
+                "lower threshold {} cannot be bigger than the higher one {} (only hue ranges wrap around)",
+                lower, higher
+            ),
+            PorterError::UnsupportedFormat(e) => write!(f, "{}", e),
+            PorterError::MaskSize { mask, image } => write!(
+                f,
+                "mask is {}x{} but the image is {}x{}",
+                mask.0, mask.1, image.0, image.1
+            ),
+            PorterError::BadPath(path) => write!(f, "bad path {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for PorterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PorterError::Io(e) => Some(e),
+            PorterError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PorterError {
+    fn from(e: std::io::Error) -> Self {
+        PorterError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for PorterError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => PorterError::Io(e),
+            image::ImageError::Unsupported(e) => PorterError::UnsupportedFormat(e.to_string()),
+            e => PorterError::Image(e),
+        }
+    }
+}
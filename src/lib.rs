@@ -0,0 +1,907 @@
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::{AnimationDecoder, Delay, Frame, GrayImage, ImageFormat, Rgba, RgbaImage};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::Duration;
+
+mod error;
+
+pub use error::PorterError;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortBy {
+    Luminance,
+    Hue,
+    Saturation,
+    Red,
+    Green,
+    Blue,
+}
+
+/// How `SortBy::Luminance` weighs the channels.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Luma {
+    /// `(r + g + b) / 3`
+    Average,
+    /// ITU-R BT.601 coefficients
+    Rec601,
+    /// ITU-R BT.709 coefficients
+    Rec709,
+    /// `max(r, g, b)`, the value of HSV
+    Max,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+    /// Lines at an arbitrary angle in degrees, going clockwise from
+    /// left-to-right, so 90 is the same as `Vertical`.
+    Angle(f32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::WebP => "webp",
+        }
+    }
+
+    /// The format the extension of `path` stands for, if it's one of these.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match ImageFormat::from_path(path).ok()? {
+            ImageFormat::Png => Some(OutputFormat::Png),
+            ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+            ImageFormat::WebP => Some(OutputFormat::WebP),
+            _ => None,
+        }
+    }
+}
+
+/// How the sorted image gets written.
+#[derive(Clone, Copy, Default)]
+pub struct Encoding {
+    /// Overrides the format inferred from the extension of the output path.
+    pub format: Option<OutputFormat>,
+    /// JPEG quality from 0 to 100, the encoder's default if there's none.
+    pub quality: Option<u8>,
+}
+
+/// Everything that controls how an image gets sorted.
+#[derive(Clone)]
+pub struct Settings {
+    /// Pixels whose property lies in `lower_threshold..=higher_threshold` get sorted.
+    /// When `lower_threshold > higher_threshold` the range wraps around instead,
+    /// which is mostly useful for hue.
+    pub lower_threshold: u16,
+    pub higher_threshold: u16,
+    pub method: SortBy,
+    pub luma: Luma,
+    pub direction: Direction,
+    pub order: Order,
+    /// Angle at which hue sorting starts, so that hues are ordered along the
+    /// color wheel instead of breaking apart between 359 and 0. Without one it's
+    /// the lower threshold of a wrapping range, and 0 otherwise.
+    pub hue_reference: Option<u16>,
+    /// Fully transparent pixels break intervals unless this is set.
+    pub include_transparent: bool,
+    /// Sort the pixels outside of the threshold range instead of inside.
+    pub invert: bool,
+    /// Shuffle the pixels of every interval instead of sorting them.
+    pub shuffle: bool,
+    /// Seed for the shuffle, a random one is picked when there's none.
+    pub seed: Option<u64>,
+    /// Decides which pixels get sorted instead of the thresholds: pixels whose
+    /// mask luminance is above 127 are sortable. Must be the size of the image.
+    pub mask: Option<GrayImage>,
+    /// Also break intervals wherever the property changes by more than this
+    /// between neighbouring pixels of a line, so that they follow the edges of objects.
+    pub edge_threshold: Option<u16>,
+    /// Intervals longer than this are split into chunks of at most this many
+    /// pixels that are sorted separately. 0 means no limit.
+    pub max_run: usize,
+    /// Order the pixels of an interval by their property in linear light instead
+    /// of the sRGB encoded values. The thresholds still apply to the sRGB values
+    /// and the pixels are written back untouched.
+    pub linear: bool,
+}
+
+impl Settings {
+    fn hue_reference(&self) -> u16 {
+        let wraps = self.lower_threshold > self.higher_threshold;
+        let reference = match self.hue_reference {
+            Some(reference) => reference,
+            None if wraps => self.lower_threshold,
+            None => 0,
+        };
+        reference % 360
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            lower_threshold: 0,
+            higher_threshold: 255,
+            method: SortBy::Luminance,
+            luma: Luma::Average,
+            direction: Direction::Horizontal,
+            order: Order::Ascending,
+            hue_reference: None,
+            include_transparent: false,
+            invert: false,
+            shuffle: false,
+            seed: None,
+            mask: None,
+            edge_threshold: None,
+            max_run: 0,
+            linear: false,
+        }
+    }
+}
+
+/// Biggest value the property of `method` can take.
+pub fn threshold_upper_boundary(method: &SortBy) -> u16 {
+    match method {
+        SortBy::Luminance | SortBy::Saturation => 255,
+        SortBy::Red | SortBy::Green | SortBy::Blue => 255,
+        SortBy::Hue => 360,
+    }
+}
+
+/// Luminance of a color for the weighted formulas, on the same scale as the channels.
+fn weighted_luminance(red: f32, green: f32, blue: f32, formula: Luma) -> f32 {
+    match formula {
+        Luma::Average => (red + green + blue) / 3.0,
+        Luma::Rec601 => 0.299 * red + 0.587 * green + 0.114 * blue,
+        Luma::Rec709 => 0.2126 * red + 0.7152 * green + 0.0722 * blue,
+        Luma::Max => red.max(green).max(blue),
+    }
+}
+
+pub fn luminance(pixel: &Rgba<u8>, formula: Luma) -> u16 {
+    match formula {
+        Luma::Average => ((pixel[0] as u16) + (pixel[1] as u16) + (pixel[2] as u16)) / 3,
+        Luma::Max => pixel[0].max(pixel[1]).max(pixel[2]) as u16,
+        _ => weighted_luminance(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, formula).round()
+            as u16,
+    }
+}
+
+/// Hue in degrees, 0 for grays. The channels can be on any scale.
+fn hue_degrees(red: f32, green: f32, blue: f32) -> f32 {
+    let min = blue.min(red.min(green));
+    let max = blue.max(red.max(green));
+
+    if max == min {
+        return 0.0;
+    }
+
+    let hue: f32 = if max == red {
+        (green - blue) / (max - min)
+    } else if max == green {
+        2.0 + (blue - red) / (max - min)
+    } else if max == blue {
+        4.0 + (red - green) / (max - min)
+    } else {
+        panic!("how?");
+    } * 60.0;
+
+    if hue < 0.0 {
+        hue + 360.0
+    } else {
+        hue
+    }
+}
+
+pub fn hue(pixel: &Rgba<u8>) -> u16 {
+    hue_degrees(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) as u16
+}
+
+/// Saturation from 0 to 1 of a color with channels from 0 to 1.
+fn saturation_fraction(red: f32, green: f32, blue: f32) -> f32 {
+    let min = blue.min(red.min(green));
+    let max = blue.max(red.max(green));
+
+    if max == min {
+        return 0.0;
+    }
+
+    let luminance = (max + min) / 2.0;
+    1.0 - ((2.0 * luminance) - 1.0).abs()
+}
+
+pub fn saturation(pixel: &Rgba<u8>) -> u16 {
+    let saturation = saturation_fraction(
+        pixel[0] as f32 / 255.0,
+        pixel[1] as f32 / 255.0,
+        pixel[2] as f32 / 255.0,
+    );
+
+    (saturation * 255.0) as u16
+}
+
+/// Converts the color channels from sRGB to linear light with the sRGB EOTF,
+/// from 0 to 1.
+pub fn to_linear(pixel: &Rgba<u8>) -> [f32; 3] {
+    let decode = |channel: u8| {
+        let value = channel as f32 / 255.0;
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    [decode(pixel[0]), decode(pixel[1]), decode(pixel[2])]
+}
+
+/// Sort key of `pixel` computed from linear light. It's kept at a finer scale
+/// than the 8 bit properties, so that the shadows that get squeezed together
+/// by the conversion are still told apart.
+fn linear_sort_key(pixel: &Rgba<u8>, settings: &Settings) -> u32 {
+    let [red, green, blue] = to_linear(pixel);
+    let scaled = |value: f32| (value * 65535.0).round() as u32;
+
+    match settings.method {
+        // hundredths of a degree along the color wheel from the reference angle
+        SortBy::Hue => {
+            let reference = settings.hue_reference() as f32;
+            ((hue_degrees(red, green, blue) - reference).rem_euclid(360.0) * 100.0) as u32
+        }
+        SortBy::Saturation => scaled(saturation_fraction(red, green, blue)),
+        SortBy::Luminance => scaled(weighted_luminance(red, green, blue, settings.luma)),
+        SortBy::Red => scaled(red),
+        SortBy::Green => scaled(green),
+        SortBy::Blue => scaled(blue),
+    }
+}
+
+pub fn red(pixel: &Rgba<u8>) -> u16 {
+    pixel[0] as u16
+}
+
+pub fn green(pixel: &Rgba<u8>) -> u16 {
+    pixel[1] as u16
+}
+
+pub fn blue(pixel: &Rgba<u8>) -> u16 {
+    pixel[2] as u16
+}
+
+/// Parallel lines of pixels at some angle that cover every pixel of an image
+/// exactly once, drawn the way Bresenham's algorithm would draw them.
+struct Lines {
+    width: u32,
+    height: u32,
+    // true when the lines are closer to vertical than to horizontal
+    steep: bool,
+    reversed: bool,
+    // change of the minor coordinate per step along the major axis
+    slope: f32,
+    first_offset: i64,
+    count: u32,
+}
+
+impl Lines {
+    fn new(width: u32, height: u32, direction: Direction) -> Self {
+        let angle: f32 = match direction {
+            Direction::Horizontal => 0.0,
+            Direction::Vertical => 90.0,
+            Direction::Angle(angle) => angle,
+        };
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let steep = sin.abs() > cos.abs();
+
+        let (major, minor, slope, reversed) = if steep {
+            (height, width, cos / sin, sin < 0.0)
+        } else {
+            (width, height, sin / cos, cos < 0.0)
+        };
+
+        // every line is shifted along the minor axis by this much at its far end,
+        // so there have to be extra lines starting outside of the image to cover the corners
+        let last_shift = Self::shift(slope, major.saturating_sub(1));
+        let first_offset = -last_shift.max(0);
+        let last_offset = minor as i64 - 1 - last_shift.min(0);
+
+        Self {
+            width,
+            height,
+            steep,
+            reversed,
+            slope,
+            first_offset,
+            count: (last_offset - first_offset + 1).max(0) as u32,
+        }
+    }
+
+    fn shift(slope: f32, position: u32) -> i64 {
+        (slope * position as f32).round() as i64
+    }
+
+    fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Coordinates of the pixels of a line in the order they are sorted in.
+    fn coordinates(&self, line: u32) -> Vec<(u32, u32)> {
+        let (major, minor) = if self.steep {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        let offset = self.first_offset + line as i64;
+
+        let mut coordinates: Vec<(u32, u32)> = (0..major)
+            .filter_map(|position| {
+                let other = offset + Self::shift(self.slope, position);
+                if other < 0 || other >= minor as i64 {
+                    return None;
+                }
+                let other = other as u32;
+                Some(if self.steep {
+                    (other, position)
+                } else {
+                    (position, other)
+                })
+            })
+            .collect();
+
+        if self.reversed {
+            coordinates.reverse();
+        }
+
+        coordinates
+    }
+}
+
+/// Turns a bitmap into a list of `(start, end)` ranges of consecutive `true`s.
+pub fn into_intervals(bitmap: Vec<bool>) -> Vec<(usize, usize)> {
+    let mut result: Vec<(usize, usize)> = Vec::new();
+    let mut interval_start: Option<usize> = None;
+
+    for (i, &sortable) in bitmap.iter().enumerate() {
+        match interval_start {
+            Some(start) if !sortable => {
+                result.push((start, i));
+                interval_start = None;
+            }
+            None if sortable => interval_start = Some(i),
+            _ => {}
+        }
+    }
+
+    if let Some(start) = interval_start {
+        result.push((start, bitmap.len()));
+    }
+
+    result
+}
+
+/// Splits every interval longer than `max_run` into chunks of at most `max_run`.
+fn split_intervals(intervals: Vec<(usize, usize)>, max_run: usize) -> Vec<(usize, usize)> {
+    if max_run == 0 {
+        return intervals;
+    }
+
+    intervals
+        .into_iter()
+        .flat_map(|(start, end)| {
+            (start..end)
+                .step_by(max_run)
+                .map(move |chunk_start| (chunk_start, end.min(chunk_start + max_run)))
+        })
+        .collect()
+}
+
+/// Whether `value` lies in `lower..=higher`, wrapping around when `lower > higher`.
+fn in_threshold_range(value: u16, lower: u16, higher: u16) -> bool {
+    if lower <= higher {
+        (lower..=higher).contains(&value)
+    } else {
+        value >= lower || value <= higher
+    }
+}
+
+fn sort_line(pixels: &mut [Rgba<u8>], mask: Option<&[bool]>, settings: &Settings, seed: u64) {
+    let pixel_property = |pixel: &Rgba<u8>| match settings.method {
+        SortBy::Hue => hue(pixel),
+        SortBy::Saturation => saturation(pixel),
+        SortBy::Luminance => luminance(pixel, settings.luma),
+        SortBy::Red => red(pixel),
+        SortBy::Green => green(pixel),
+        SortBy::Blue => blue(pixel),
+    };
+
+    let accepted =
+        |value: u16| in_threshold_range(value, settings.lower_threshold, settings.higher_threshold);
+
+    let values: Vec<u16> = pixels.iter().map(pixel_property).collect();
+
+    let is_edge = |i: usize| match settings.edge_threshold {
+        Some(threshold) if i > 0 => {
+            let difference = values[i].abs_diff(values[i - 1]);
+            let difference = match settings.method {
+                // hue is on a circle, so 359 and 0 are neighbours
+                SortBy::Hue => difference.min(360 - difference),
+                _ => difference,
+            };
+            difference > threshold
+        }
+        _ => false,
+    };
+
+    let pixel_bitmap: Vec<bool> = pixels
+        .iter()
+        .enumerate()
+        .map(|(i, pixel)| {
+            let sortable = match mask {
+                Some(mask) => mask[i],
+                None => accepted(values[i]),
+            };
+            (settings.include_transparent || pixel[3] != 0)
+                && (sortable != settings.invert)
+                && !is_edge(i)
+        })
+        .collect();
+
+    let intervals = split_intervals(into_intervals(pixel_bitmap), settings.max_run);
+
+    if settings.shuffle {
+        let mut rng = StdRng::seed_from_u64(seed);
+        for (start, end) in intervals {
+            pixels[start..end].shuffle(&mut rng);
+        }
+        return;
+    }
+
+    // the thresholds and edges always use the sRGB values, only the order
+    // of the pixels within an interval depends on `settings.linear`
+    let reference = settings.hue_reference();
+    let keys: Vec<u32> = if settings.linear {
+        pixels
+            .iter()
+            .map(|pixel| linear_sort_key(pixel, settings))
+            .collect()
+    } else {
+        values
+            .iter()
+            .map(|&value| match settings.method {
+                // distance along the color wheel from the reference angle
+                SortBy::Hue => ((value + 360 - reference) % 360) as u32,
+                _ => value as u32,
+            })
+            .collect()
+    };
+
+    for (start, end) in intervals {
+        let mut keyed: Vec<(u32, Rgba<u8>)> = keys[start..end]
+            .iter()
+            .copied()
+            .zip(pixels[start..end].iter().copied())
+            .collect();
+        match settings.order {
+            Order::Ascending => keyed.sort_by_key(|&(key, _)| key),
+            Order::Descending => keyed.sort_by_key(|&(key, _)| std::cmp::Reverse(key)),
+        }
+        for (pixel, (_, sorted)) in pixels[start..end].iter_mut().zip(keyed) {
+            *pixel = sorted;
+        }
+    }
+}
+
+pub fn sort_image_buffer(image: &mut RgbaImage, settings: &Settings) -> Result<(), PorterError> {
+    sort_image_buffer_with_progress(image, settings, &mut |_| {})
+}
+
+/// Lines are sorted in batches of at least this many, `progress` gets called after each batch.
+const LINES_PER_PROGRESS_STEP: u32 = 64;
+
+/// Same as `sort_image_buffer`, calling `progress` with the fraction of the
+/// image that's sorted so far, from 0.0 to 1.0, as the lines get done.
+pub fn sort_image_buffer_with_progress(
+    image: &mut RgbaImage,
+    settings: &Settings,
+    progress: &mut dyn FnMut(f32),
+) -> Result<(), PorterError> {
+    let width = image.width();
+    let height = image.height();
+
+    let boundary = threshold_upper_boundary(&settings.method);
+    if settings.lower_threshold > boundary || settings.higher_threshold > boundary {
+        return Err(PorterError::BadThreshold {
+            lower: settings.lower_threshold,
+            higher: settings.higher_threshold,
+            boundary,
+        });
+    }
+
+    if let Some(mask) = &settings.mask {
+        if mask.dimensions() != image.dimensions() {
+            return Err(PorterError::MaskSize {
+                mask: mask.dimensions(),
+                image: image.dimensions(),
+            });
+        }
+    }
+
+    let lines = Lines::new(width, height, settings.direction);
+
+    // every line gets its own seed so that the result doesn't depend on
+    // the order in which the lines are processed
+    let seed = settings.seed.unwrap_or_else(rand::random);
+
+    // every line is independent, so they are copied out of the buffer,
+    // sorted concurrently and only then written back. lines don't share
+    // pixels, so a batch can be written back before the next one is read
+    let count = lines.count();
+    let step = (count / 100).max(LINES_PER_PROGRESS_STEP);
+    progress(0.0);
+    for batch_start in (0..count).step_by(step as usize) {
+        let batch = batch_start..(batch_start + step).min(count);
+
+        let source = &*image;
+        // the coordinates are kept along with the pixels for the write back
+        let sorted_lines: Vec<_> = batch
+            .clone()
+            .into_par_iter()
+            .map(|line| {
+                let coordinates = lines.coordinates(line);
+                let mut pixels: Vec<Rgba<u8>> = coordinates
+                    .iter()
+                    .map(|&(x, y)| *source.get_pixel(x, y))
+                    .collect();
+                let mask: Option<Vec<bool>> = settings.mask.as_ref().map(|mask| {
+                    coordinates
+                        .iter()
+                        .map(|&(x, y)| mask.get_pixel(x, y)[0] > 127)
+                        .collect()
+                });
+                sort_line(
+                    &mut pixels,
+                    mask.as_deref(),
+                    settings,
+                    seed.wrapping_add(line as u64),
+                );
+                (coordinates, pixels)
+            })
+            .collect();
+
+        for (coordinates, pixels) in sorted_lines {
+            for ((x, y), pixel) in coordinates.into_iter().zip(pixels) {
+                image.put_pixel(x, y, pixel);
+            }
+        }
+
+        progress(batch.end as f32 / count as f32);
+    }
+
+    Ok(())
+}
+
+/// Writes `image` to `output` with `encoding`, anything that isn't one of the
+/// `OutputFormat`s is left to `image` to figure out from the extension.
+pub fn save_image(
+    image: &RgbaImage,
+    output: &Path,
+    encoding: &Encoding,
+) -> Result<(), PorterError> {
+    let format = encoding.format.or_else(|| OutputFormat::from_path(output));
+    match format {
+        Some(OutputFormat::Png) => image.save_with_format(output, ImageFormat::Png)?,
+        Some(OutputFormat::Jpeg) => {
+            let writer = BufWriter::new(File::create(output)?);
+            let mut encoder = match encoding.quality {
+                Some(quality) => JpegEncoder::new_with_quality(writer, quality),
+                None => JpegEncoder::new(writer),
+            };
+            encoder.encode_image(image)?;
+        }
+        // `image` only writes lossless WebP, so there's no quality to set
+        Some(OutputFormat::WebP) => image.save_with_format(output, ImageFormat::WebP)?,
+        None => image.save(output)?,
+    }
+    Ok(())
+}
+
+fn write_gif(output: &Path, frames: Vec<Frame>) -> Result<(), PorterError> {
+    let mut encoder = GifEncoder::new(BufWriter::new(File::create(output)?));
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(frames)?;
+    Ok(())
+}
+
+/// Sorts every frame of an animation the same way and keeps their delays.
+fn sort_frames(
+    frames: Vec<Frame>,
+    settings: &Settings,
+    progress: &mut dyn FnMut(f32),
+) -> Result<Vec<Frame>, PorterError> {
+    let count = frames.len() as f32;
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let delay = frame.delay();
+            let mut buffer = frame.into_buffer();
+            sort_image_buffer_with_progress(&mut buffer, settings, &mut |done| {
+                progress((i as f32 + done) / count)
+            })?;
+            Ok(Frame::from_parts(buffer, 0, 0, delay))
+        })
+        .collect()
+}
+
+/// Sorts the image at `input` and writes the result to `output`.
+/// Animated GIFs get every frame sorted and are written back as animated GIFs,
+/// so their `output` has to end in `.gif`.
+/// `progress` gets the fraction of the sorting that's done, from 0.0 to 1.0.
+pub fn sort_image(
+    input: &Path,
+    output: &Path,
+    settings: &Settings,
+    encoding: &Encoding,
+    progress: &mut dyn FnMut(f32),
+) -> Result<(), PorterError> {
+    let reader = image::io::Reader::open(input)?.with_guessed_format()?;
+
+    if reader.format() == Some(ImageFormat::Gif) {
+        let decoder = GifDecoder::new(BufReader::new(File::open(input)?))?;
+        let frames = decoder.into_frames().collect_frames()?;
+        if frames.len() > 1 {
+            let gif_output = output
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+            if encoding.format.is_some() || !gif_output {
+                return Err(PorterError::UnsupportedFormat(
+                    "animated images can only be written as GIFs".to_string(),
+                ));
+            }
+            return write_gif(output, sort_frames(frames, settings, progress)?);
+        }
+    }
+
+    let mut image = reader.decode()?.into_rgba8();
+    sort_image_buffer_with_progress(&mut image, settings, progress)?;
+    save_image(&image, output, encoding)
+}
+
+/// Sorts a copy of `image` for every frame, sweeping the higher threshold from
+/// the lower one up to `settings.higher_threshold`. `progress` gets the
+/// fraction of the frames that's done, from 0.0 to 1.0.
+pub fn threshold_sweep(
+    image: &RgbaImage,
+    settings: &Settings,
+    frames: u32,
+    progress: &mut dyn FnMut(f32),
+) -> Result<Vec<RgbaImage>, PorterError> {
+    let lower = settings.lower_threshold as u32;
+    let higher = settings.higher_threshold as u32;
+
+    // wrapping ranges sweep through the top of the range and start over from 0
+    let period = match settings.method {
+        SortBy::Hue => 360,
+        _ => threshold_upper_boundary(&settings.method) as u32 + 1,
+    };
+    let span = if lower <= higher {
+        higher - lower
+    } else {
+        higher + period - lower
+    };
+
+    (0..frames)
+        .map(|frame| {
+            let step = match frames {
+                1 => span,
+                _ => span * frame / (frames - 1),
+            };
+            let higher_threshold = if lower <= higher {
+                lower + step
+            } else {
+                (lower + step) % period
+            };
+
+            let frame_settings = Settings {
+                higher_threshold: higher_threshold as u16,
+                ..settings.clone()
+            };
+
+            let mut frame_image = image.clone();
+            sort_image_buffer_with_progress(&mut frame_image, &frame_settings, &mut |done| {
+                progress((frame as f32 + done) / frames as f32)
+            })?;
+            Ok(frame_image)
+        })
+        .collect()
+}
+
+/// Writes an animated GIF of the threshold sweep of the image at `input` to `output`.
+pub fn sort_image_animated(
+    input: &Path,
+    output: &Path,
+    settings: &Settings,
+    frames: u32,
+    frame_delay: Duration,
+    progress: &mut dyn FnMut(f32),
+) -> Result<(), PorterError> {
+    let image = image::open(input)?.into_rgba8();
+    let frames = threshold_sweep(&image, settings, frames, progress)?
+        .into_iter()
+        .map(|frame| Frame::from_parts(frame, 0, 0, Delay::from_saturating_duration(frame_delay)))
+        .collect();
+
+    write_gif(output, frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_of_bitmap() {
+        assert_eq!(into_intervals(vec![]), vec![]);
+        assert_eq!(into_intervals(vec![false, false]), vec![]);
+        assert_eq!(into_intervals(vec![true, true, true]), vec![(0, 3)]);
+        assert_eq!(
+            into_intervals(vec![true, false, true, true, false, false, true]),
+            vec![(0, 1), (2, 4), (6, 7)]
+        );
+    }
+
+    #[test]
+    fn split_long_intervals() {
+        let intervals = vec![(0, 5), (7, 8), (10, 14)];
+        assert_eq!(split_intervals(intervals.clone(), 0), intervals);
+        assert_eq!(
+            split_intervals(intervals.clone(), 2),
+            vec![(0, 2), (2, 4), (4, 5), (7, 8), (10, 12), (12, 14)]
+        );
+        assert_eq!(split_intervals(intervals.clone(), 10), intervals);
+    }
+
+    #[test]
+    fn threshold_range() {
+        assert!(in_threshold_range(0, 0, 255));
+        assert!(in_threshold_range(255, 0, 255));
+        assert!(in_threshold_range(69, 69, 69));
+        assert!(!in_threshold_range(70, 0, 69));
+
+        // wraps around, e.g. 300..=30 for the reds
+        assert!(in_threshold_range(300, 300, 30));
+        assert!(in_threshold_range(359, 300, 30));
+        assert!(in_threshold_range(0, 300, 30));
+        assert!(in_threshold_range(30, 300, 30));
+        assert!(!in_threshold_range(31, 300, 30));
+        assert!(!in_threshold_range(299, 300, 30));
+    }
+
+    #[test]
+    fn lines_cover_every_pixel_once() {
+        let sizes = [(1, 1), (1, 9), (9, 1), (7, 3), (3, 7), (10, 10), (64, 17)];
+        let angles = [
+            0.0, 13.7, 30.0, 45.0, 60.0, 89.0, 90.0, 135.0, 180.0, 200.0, 270.0, -30.0, -45.0,
+        ];
+
+        for (width, height) in sizes {
+            for angle in angles {
+                let lines = Lines::new(width, height, Direction::Angle(angle));
+                let mut covered = vec![0; (width * height) as usize];
+                for line in 0..lines.count() {
+                    for (x, y) in lines.coordinates(line) {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+                assert!(
+                    covered.iter().all(|&count| count == 1),
+                    "{}x{} at {} degrees",
+                    width,
+                    height,
+                    angle
+                );
+            }
+        }
+    }
+
+    fn gray(value: u8) -> Rgba<u8> {
+        Rgba([value, value, value, 255])
+    }
+
+    #[test]
+    fn transparent_pixels_break_intervals() {
+        let transparent = Rgba([255, 255, 255, 0]);
+        let row = [gray(200), gray(100), transparent, gray(50), gray(10)];
+        let mut image = RgbaImage::from_fn(5, 1, |x, _| row[x as usize]);
+
+        sort_image_buffer(&mut image, &Settings::default()).unwrap();
+        let sorted: Vec<Rgba<u8>> = image.pixels().copied().collect();
+        assert_eq!(
+            sorted,
+            vec![gray(100), gray(200), transparent, gray(10), gray(50)]
+        );
+
+        let mut image = RgbaImage::from_fn(5, 1, |x, _| row[x as usize]);
+        let settings = Settings {
+            include_transparent: true,
+            ..Settings::default()
+        };
+        sort_image_buffer(&mut image, &settings).unwrap();
+        let sorted: Vec<Rgba<u8>> = image.pixels().copied().collect();
+        assert_eq!(
+            sorted,
+            vec![gray(10), gray(50), gray(100), gray(200), transparent]
+        );
+    }
+
+    #[test]
+    fn linear_sort_keeps_shadows_apart() {
+        let mut image = RgbaImage::from_fn(20, 1, |x, _| gray(19 - x as u8));
+        let settings = Settings {
+            linear: true,
+            ..Settings::default()
+        };
+
+        sort_image_buffer(&mut image, &settings).unwrap();
+        let sorted: Vec<Rgba<u8>> = image.pixels().copied().collect();
+        assert_eq!(sorted, (0..20).map(gray).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn wrapping_hue_range_sorts_across_zero() {
+        let hue_10 = Rgba([255, 43, 0, 255]);
+        let hue_20 = Rgba([255, 85, 0, 255]);
+        let hue_340 = Rgba([255, 0, 85, 255]);
+        let hue_350 = Rgba([255, 0, 43, 255]);
+        let row = [hue_10, hue_350, hue_20, hue_340];
+        let mut image = RgbaImage::from_fn(4, 1, |x, _| row[x as usize]);
+
+        let settings = Settings {
+            method: SortBy::Hue,
+            lower_threshold: 300,
+            higher_threshold: 30,
+            ..Settings::default()
+        };
+        sort_image_buffer(&mut image, &settings).unwrap();
+        let sorted: Vec<Rgba<u8>> = image.pixels().copied().collect();
+        assert_eq!(sorted, vec![hue_340, hue_350, hue_10, hue_20]);
+    }
+
+    #[test]
+    fn seeded_shuffle_is_reproducible() {
+        let original = RgbaImage::from_fn(40, 30, |x, y| gray((x * 6 + y) as u8));
+        let settings = Settings {
+            shuffle: true,
+            seed: Some(42),
+            ..Settings::default()
+        };
+
+        let mut first = original.clone();
+        sort_image_buffer(&mut first, &settings).unwrap();
+        let mut second = original.clone();
+        sort_image_buffer(&mut second, &settings).unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, original);
+    }
+}
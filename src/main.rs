@@ -1,290 +1,702 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
 use eframe::egui;
-use image;
+use indicatif::{ProgressBar, ProgressStyle};
+use psorter::{sort_image, sort_image_animated, sort_image_buffer, threshold_upper_boundary};
+use psorter::{Direction, Encoding, Luma, Order, OutputFormat, PorterError, Settings, SortBy};
+use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-enum SortBy {
+fn basename(path: &Path) -> Result<String, PorterError> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| PorterError::BadPath(path.to_path_buf()))
+}
+
+fn parse_angle(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(angle) if angle.is_finite() => Ok(angle),
+        Ok(_) => Err("the angle has to be a finite number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Method {
+    #[value(name = "l")]
     Luminance,
+    #[value(name = "h")]
     Hue,
+    #[value(name = "s")]
     Saturation,
+    #[value(name = "r")]
+    Red,
+    #[value(name = "g")]
+    Green,
+    #[value(name = "b")]
+    Blue,
 }
 
-fn threshold_upper_boundary(method: &SortBy) -> u16 {
-    match method {
-        SortBy::Luminance | SortBy::Saturation => 255,
-        SortBy::Hue => 360,
+impl From<Method> for SortBy {
+    fn from(method: Method) -> Self {
+        match method {
+            Method::Luminance => SortBy::Luminance,
+            Method::Hue => SortBy::Hue,
+            Method::Saturation => SortBy::Saturation,
+            Method::Red => SortBy::Red,
+            Method::Green => SortBy::Green,
+            Method::Blue => SortBy::Blue,
+        }
     }
 }
 
-fn luminance(pixel: &egui::Color32) -> u16 {
-    ((pixel.r() as u16) + (pixel.g() as u16) + (pixel.b() as u16)) / 3
+#[derive(Clone, Copy, ValueEnum)]
+enum LumaFormula {
+    Rec601,
+    Rec709,
+    Average,
+    Max,
 }
 
-fn hue(pixel: &egui::Color32) -> u16 {
-    let red = pixel.r() as f32;
-    let green = pixel.g() as f32;
-    let blue = pixel.b() as f32;
+impl From<LumaFormula> for Luma {
+    fn from(formula: LumaFormula) -> Self {
+        match formula {
+            LumaFormula::Rec601 => Luma::Rec601,
+            LumaFormula::Rec709 => Luma::Rec709,
+            LumaFormula::Average => Luma::Average,
+            LumaFormula::Max => Luma::Max,
+        }
+    }
+}
 
-    let min = blue.min(red.min(green));
-    let max = blue.max(red.max(green));
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Png,
+    Jpeg,
+    Webp,
+}
 
-    if max == min {
-        return 0;
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Png => OutputFormat::Png,
+            Format::Jpeg => OutputFormat::Jpeg,
+            Format::Webp => OutputFormat::WebP,
+        }
     }
+}
 
-    let hue: f32 = if max == red {
-        (green - blue) / (max - min)
-    } else if max == green {
-        2.0 + (blue - red) / (max - min)
-    } else if max == blue {
-        4.0 + (red - green) / (max - min)
-    } else {
-        panic!("how?");
-    } * 60.0;
+/// Pixel SORTER. Run without any arguments to open the GUI.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// What the pixels are sorted by: l (luminance), h (hue), s (saturation),
+    /// r (red), g (green) or b (blue)
+    #[arg(value_enum, hide_possible_values = true)]
+    method: Method,
+
+    /// Lower threshold of the property of the pixels that get sorted
+    lower: u16,
+
+    /// Higher threshold of the property of the pixels that get sorted
+    higher: u16,
+
+    /// Images to sort, directories get searched for images
+    #[arg(required = true)]
+    images: Vec<PathBuf>,
+
+    /// Search directories for images recursively
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// How luminance is computed
+    #[arg(long, value_enum, default_value_t = LumaFormula::Average)]
+    luma: LumaFormula,
+
+    /// Sort columns instead of rows
+    #[arg(short, long)]
+    vertical: bool,
+
+    /// Sort along lines at this angle (degrees, clockwise from left-to-right)
+    #[arg(
+        long,
+        value_name = "DEGREES",
+        allow_negative_numbers = true,
+        conflicts_with = "vertical",
+        value_parser = parse_angle
+    )]
+    angle: Option<f32>,
+
+    /// Sort from the highest value to the lowest
+    #[arg(short, long)]
+    descending: bool,
+
+    /// Sort the pixels outside of the threshold range instead
+    #[arg(short, long)]
+    invert: bool,
+
+    /// Shuffle the pixels of every interval instead of sorting them
+    #[arg(long)]
+    shuffle: bool,
+
+    /// Seed for --shuffle, random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Sort only where this black and white image is white, ignoring the thresholds
+    #[arg(long, value_name = "PATH")]
+    mask: Option<PathBuf>,
+
+    /// Angle hue sorting starts at, the lower threshold of a wrapping range or 0 by default
+    #[arg(long, value_name = "DEGREES")]
+    hue_reference: Option<u16>,
+
+    /// Also break intervals where the property jumps by more than this between neighbours
+    #[arg(long, value_name = "THRESHOLD")]
+    edge_threshold: Option<u16>,
+
+    /// Split intervals longer than this many pixels into separately sorted chunks, 0 for no limit
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    max_run: usize,
 
-    (if hue < 0.0 { hue + 360.0 } else { hue }) as u16
+    /// Sort fully transparent pixels too
+    #[arg(long)]
+    include_transparent: bool,
+
+    /// Order the pixels by their property in linear light instead of the sRGB values
+    #[arg(long)]
+    linear: bool,
+
+    /// Write an animated GIF of this many frames, sweeping the higher threshold up from the lower one
+    #[arg(long, value_name = "FRAMES", value_parser = clap::value_parser!(u32).range(1..))]
+    animate: Option<u32>,
+
+    /// Frames per second of the animation
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    fps: u32,
+
+    /// Delay between the frames of the animation in milliseconds, instead of --fps
+    #[arg(long, value_name = "MS", conflicts_with = "fps")]
+    delay: Option<u64>,
+
+    /// Format of the sorted images, instead of the one of the original
+    #[arg(long, value_enum, conflicts_with = "animate")]
+    format: Option<Format>,
+
+    /// Quality of JPEG output, from 0 to 100 (WebP is always lossless)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    quality: Option<u8>,
+
+    /// Directory the sorted images are written to
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+
+    /// Prefix of the names of the sorted images
+    #[arg(long, default_value = "sorted-")]
+    prefix: String,
+
+    /// Path of the sorted image, when sorting a single one
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["out_dir", "prefix"])]
+    out: Option<PathBuf>,
+
+    /// Skip images whose output already exists instead of replacing it
+    #[arg(long)]
+    no_clobber: bool,
+
+    /// Sort the images in place, replacing the originals
+    #[arg(
+        long,
+        conflicts_with_all = ["out", "out_dir", "prefix", "format", "animate", "no_clobber"]
+    )]
+    overwrite: bool,
 }
 
-fn saturation(pixel: &egui::Color32) -> u16 {
-    let red = pixel.r() as f32 / 255.0;
-    let green = pixel.g() as f32 / 255.0;
-    let blue = pixel.b() as f32 / 255.0;
+fn main() {
+    if env::args().len() <= 1 {
+        if gui_main().is_err() {
+            std::process::exit(1);
+        } else {
+            std::process::exit(0);
+        }
+    }
 
-    let min = blue.min(red.min(green));
-    let max = blue.max(red.max(green));
+    let cli = Cli::parse();
 
-    if max == min {
-        return 0;
+    if let Err(e) = validate_thresholds(&cli) {
+        Cli::command().error(ErrorKind::ValueValidation, e).exit();
     }
 
-    let luminance = (max + min) / 2.0;
-    let saturation = 1.0 - ((2.0 * luminance) - 1.0).abs();
+    let mut failed = false;
+    let mut jobs: Vec<Job> = Vec::new();
+    for path in &cli.images {
+        if !path.is_dir() {
+            jobs.push(Job {
+                path: path.clone(),
+                relative_dir: PathBuf::new(),
+            });
+            continue;
+        }
 
-    (saturation * 255.0) as u16
-}
+        if let Err(e) = collect_images(path, path, cli.recursive, &mut jobs) {
+            eprintln!("ERROR: cannot read directory {}: {}", path.display(), e);
+            failed = true;
+        }
+    }
 
-fn basename(path: &str) -> String {
-    let path = Path::new(&path);
-    path.file_name().unwrap().to_str().unwrap().to_string()
-}
+    if cli.out.is_some() && jobs.len() > 1 {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--out can only be used when sorting a single image",
+            )
+            .exit();
+    }
 
-fn into_intervals(bitmap: Vec<bool>) -> Vec<(usize, usize)> {
-    let mut result: Vec<(usize, usize)> = Vec::new();
-    let mut interval_start: Option<usize> = None;
+    // `sort_image_animated` always writes a GIF, whatever the extension says
+    if let (Some(_), Some(out)) = (cli.animate, &cli.out) {
+        let is_gif = out
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+        if !is_gif {
+            Cli::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    "--out has to end in .gif when used with --animate",
+                )
+                .exit();
+        }
+    }
 
-    for i in 0..bitmap.len() {
-        if bitmap[i] == false && interval_start.is_some() {
-            result.push((interval_start.unwrap(), i));
-            interval_start = None;
-        } else if bitmap[i] == true && interval_start.is_none() {
-            interval_start = Some(i);
+    // `save_image` uses the format over the extension, so they have to agree
+    if let (Some(format), Some(out)) = (cli.format, &cli.out) {
+        if OutputFormat::from_path(out) != Some(format.into()) {
+            Cli::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    "the extension of --out has to match --format",
+                )
+                .exit();
         }
     }
 
-    if interval_start.is_some() {
-        result.push((interval_start.unwrap(), bitmap.len()));
+    if cli.quality.is_some() && matches!(cli.format, Some(Format::Png | Format::Webp)) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--quality only applies to --format jpeg",
+            )
+            .exit();
     }
 
-    result
-}
+    let mask = match cli.mask.as_ref().map(image::open).transpose() {
+        Ok(mask) => mask.map(|mask| mask.to_luma8()),
+        Err(e) => {
+            let path = cli.mask.as_ref().unwrap();
+            eprintln!("ERROR: cannot load mask {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let settings = settings_from_cli(&cli, mask);
+
+    if let Some(dir) = &cli.out_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("ERROR: cannot create directory {}: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    }
 
-fn sort_image(
-    lower_threshold: u16,
-    higher_threshold: u16,
-    image: &mut egui::ColorImage,
-    sorting_method: &SortBy,
-) {
-    let width = image.width();
-    let height = image.height();
-
-    let pixel_property = match sorting_method {
-        SortBy::Hue => hue,
-        SortBy::Saturation => saturation,
-        SortBy::Luminance => luminance,
+    let encoding = Encoding {
+        format: cli.format.map(OutputFormat::from),
+        quality: cli.quality,
     };
 
-    for yi in 0..height {
-        let intervals = {
-            let mut pixel_bitmap: Vec<bool> = Vec::with_capacity(width as usize);
-            for xi in 0..width {
-                let pixel: egui::Color32 = image.pixels[yi * width + xi];
-                let value = pixel_property(&pixel);
-                let accepted_range = lower_threshold..=higher_threshold;
-                pixel_bitmap.push(accepted_range.contains(&value));
-            }
+    // drawn on stderr, and not at all when it isn't a terminal. messages are
+    // printed through `suspend` so that they don't end up on the line of the bar
+    let bar = ProgressBar::new(PROGRESS_BAR_LENGTH).with_style(
+        ProgressStyle::with_template("{prefix} [{bar:40}] {percent:>3}% {msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
 
-            into_intervals(pixel_bitmap)
-        };
+    let outputs: Vec<Result<PathBuf, PorterError>> = jobs
+        .iter()
+        .map(|job| output_path(&cli, &job.path, &job.relative_dir))
+        .collect();
+
+    // the first image that maps to an output gets it, the others are refused
+    let mut output_owners: HashMap<PathBuf, usize> = HashMap::new();
+    for (index, output) in outputs.iter().enumerate() {
+        if let Ok(output) = output {
+            output_owners
+                .entry(output.components().collect())
+                .or_insert(index);
+        }
+    }
 
-        for interval in intervals {
-            let (start, end) = interval;
-            let mut pixels: Vec<egui::Color32> = Vec::with_capacity(end - start);
-            for xi in start..end {
-                pixels.push(image.pixels[yi * width + xi]);
+    for (index, (Job { path, .. }, output)) in jobs.iter().zip(outputs).enumerate() {
+        let new_file_name = match output {
+            Ok(new_file_name) => new_file_name,
+            Err(e) => {
+                bar.suspend(|| eprintln!("ERROR: cannot sort image {}: {}", path.display(), e));
+                failed = true;
+                continue;
             }
-            pixels.sort_by(|a, b| pixel_property(&a).cmp(&pixel_property(&b)));
+        };
+
+        let owner = output_owners[&new_file_name.components().collect::<PathBuf>()];
+        if owner != index {
+            bar.suspend(|| {
+                eprintln!(
+                    "ERROR: cannot sort image {}: {} is already the output of {}",
+                    path.display(),
+                    new_file_name.display(),
+                    jobs[owner].path.display()
+                )
+            });
+            failed = true;
+            continue;
+        }
+
+        if cli.no_clobber && new_file_name.exists() {
+            bar.suspend(|| {
+                eprintln!(
+                    "skipping {}: {} already exists",
+                    path.display(),
+                    new_file_name.display()
+                )
+            });
+            continue;
+        }
+
+        if !cli.overwrite && is_same_file(path, &new_file_name) {
+            bar.suspend(|| {
+                eprintln!(
+                    "ERROR: cannot sort image {}: it would be overwritten, pass --overwrite to sort it in place",
+                    path.display()
+                )
+            });
+            failed = true;
+            continue;
+        }
 
-            for i in 0..pixels.len() {
-                let xi = start + i;
-                image.pixels[yi * width + xi] = pixels[i];
+        bar.set_prefix(format!("image {}/{}", index + 1, jobs.len()));
+        bar.set_message(path.display().to_string());
+        bar.set_position(0);
+        let mut progress = |done: f32| bar.set_position((done * PROGRESS_BAR_LENGTH as f32) as u64);
+
+        let result = match cli.animate {
+            Some(frames) => {
+                let delay = match cli.delay {
+                    Some(delay) => Duration::from_millis(delay),
+                    None => Duration::from_secs(1) / cli.fps,
+                };
+                sort_image_animated(
+                    path,
+                    &new_file_name,
+                    &settings,
+                    frames,
+                    delay,
+                    &mut progress,
+                )
             }
+            None => sort_image(path, &new_file_name, &settings, &encoding, &mut progress),
+        };
+
+        // keep going, one broken image shouldn't stop the whole batch
+        if let Err(e) = result {
+            bar.suspend(|| eprintln!("ERROR: cannot sort image {}: {}", path.display(), e));
+            failed = true;
         }
     }
+
+    bar.finish_and_clear();
+
+    if failed {
+        std::process::exit(1);
+    }
 }
 
-fn main() {
-    let mut args: Vec<String> = env::args().skip(1).collect();
+const PROGRESS_BAR_LENGTH: u64 = 1000;
 
-    if args.len() == 0 {
-        if gui_main().is_err() {
-            std::process::exit(1);
+fn validate_thresholds(cli: &Cli) -> Result<(), PorterError> {
+    let boundary = threshold_upper_boundary(&cli.method.into());
+
+    if cli.higher.max(cli.lower) > boundary {
+        return Err(PorterError::BadThreshold {
+            lower: cli.lower,
+            higher: cli.higher,
+            boundary,
+        });
+    }
+
+    // a hue range is allowed to wrap around 360, e.g. 300..=30 for the reds
+    if cli.lower > cli.higher && cli.method != Method::Hue {
+        return Err(PorterError::ReversedThreshold {
+            lower: cli.lower,
+            higher: cli.higher,
+        });
+    }
+
+    Ok(())
+}
+
+fn settings_from_cli(cli: &Cli, mask: Option<image::GrayImage>) -> Settings {
+    let direction = match cli.angle {
+        Some(angle) => Direction::Angle(angle),
+        None if cli.vertical => Direction::Vertical,
+        None => Direction::Horizontal,
+    };
+
+    Settings {
+        lower_threshold: cli.lower,
+        higher_threshold: cli.higher,
+        method: cli.method.into(),
+        luma: cli.luma.into(),
+        direction,
+        order: if cli.descending {
+            Order::Descending
         } else {
-            std::process::exit(0);
-        }
-    } else if args.len() < 4 {
-        eprintln!("USAGE: psorter <l/h/s> <lower threshold> <higher threshold> [images]");
-        std::process::exit(1);
+            Order::Ascending
+        },
+        hue_reference: cli.hue_reference,
+        include_transparent: cli.include_transparent,
+        invert: cli.invert,
+        shuffle: cli.shuffle,
+        seed: cli.seed,
+        mask,
+        edge_threshold: cli.edge_threshold,
+        max_run: cli.max_run,
+        linear: cli.linear,
     }
+}
+
+/// An image to sort, along with the directory it's in relative to the
+/// directory argument it was found in.
+struct Job {
+    path: PathBuf,
+    relative_dir: PathBuf,
+}
+
+fn has_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn collect_images(
+    root: &Path,
+    dir: &Path,
+    recursive: bool,
+    jobs: &mut Vec<Job>,
+) -> Result<(), PorterError> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
 
-    let sorting_method = {
-        let arg = args.first().expect("ERROR: please choose one of the methods of sorting (l for luminance, h for hue and s for saturation) as a first argument");
-        match arg.as_str() {
-            "l" => SortBy::Luminance,
-            "h" => SortBy::Hue,
-            "s" => SortBy::Saturation,
-            _ => {
-                eprintln!("ERROR: sorting method must be one of the following: l (luminance), h (hue) or s (saturation)");
-                std::process::exit(1);
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                collect_images(root, &path, recursive, jobs)?;
             }
+        } else if has_image_extension(&path) {
+            jobs.push(Job {
+                relative_dir: dir
+                    .strip_prefix(root)
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf(),
+                path,
+            });
         }
-    };
-    args.remove(0);
-
-    let lower_threshold = args
-        .first()
-        .expect("ERROR: please provide lower threshold as a second argument")
-        .parse::<u16>()
-        .expect("ERROR: threshold must be an integer");
-    args.remove(0);
-
-    let higher_threshold = args
-        .first()
-        .expect("ERROR: please provide higher threshold as a third argument")
-        .parse::<u16>()
-        .expect("ERROR: threshold must be an integer");
-    args.remove(0);
-
-    if lower_threshold > higher_threshold {
-        eprintln!("ERROR: lower threshold cannot be bigger than a higher threshold.");
-        std::process::exit(1);
     }
 
-    for path in args {
-        let mut image = match load_image_from_path(&path) {
-            Ok(new_image) => new_image,
-            Err(e) => {
-                eprintln!("ERROR: cannot load image {}: {}", path, e);
-                std::process::exit(1);
-            }
-        };
+    Ok(())
+}
+
+fn output_path(cli: &Cli, path: &Path, relative_dir: &Path) -> Result<PathBuf, PorterError> {
+    if let Some(out) = &cli.out {
+        return Ok(out.clone());
+    }
+
+    if cli.overwrite {
+        return Ok(path.to_path_buf());
+    }
+
+    // don't stack prefixes when sorting an already sorted image again
+    let name = basename(path)?;
+    let name = if name.starts_with(&cli.prefix) {
+        name
+    } else {
+        format!("{}{}", cli.prefix, name)
+    };
+    // images found in directories keep their subdirectories, so that ones
+    // with the same name in different subdirectories don't overwrite each other
+    let dir = match &cli.out_dir {
+        Some(dir) => dir.join(relative_dir),
+        None => relative_dir.to_path_buf(),
+    };
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(name);
+
+    Ok(match (cli.animate, cli.format) {
+        (Some(_), _) => path.with_extension("gif"),
+        (None, Some(format)) => path.with_extension(OutputFormat::from(format).extension()),
+        (None, None) => path,
+    })
+}
 
-        sort_image(
-            lower_threshold,
-            higher_threshold,
-            &mut image,
-            &sorting_method,
-        );
-
-        let new_file_name = format!("sorted-{}", basename(&path));
-        image::save_buffer(
-            &new_file_name,
-            image.as_raw(),
-            image.width() as u32,
-            image.height() as u32,
-            image::ColorType::Rgba8,
-        )
-        .expect(&format!("ERROR: failed to save file {}", &new_file_name));
+/// Whether both paths lead to the same existing file.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
     }
 }
 
-fn load_image_from_path(path: &str) -> Result<egui::ColorImage, image::ImageError> {
-    let image = image::io::Reader::open(path)?.decode()?;
-    let size = [image.width() as _, image.height() as _];
-    let image_buffer = image.to_rgba8();
-    let pixels = image_buffer.as_flat_samples();
-    Ok(egui::ColorImage::from_rgba_unmultiplied(
-        size,
-        pixels.as_slice(),
-    ))
+fn load_image_from_path(path: &str) -> Result<image::RgbaImage, PorterError> {
+    Ok(image::open(path)?.into_rgba8())
+}
+
+fn to_color_image(image: &image::RgbaImage) -> egui::ColorImage {
+    egui::ColorImage::from_rgba_unmultiplied(
+        [image.width() as _, image.height() as _],
+        image.as_flat_samples().as_slice(),
+    )
 }
 
-const IMAGE_EXTENSIONS: [&'static str; 8] =
-    ["png", "jpg", "jpeg", "bmp", "ico", "tiff", "webp", "tga"];
+const IMAGE_EXTENSIONS: [&str; 9] = [
+    "png", "jpg", "jpeg", "bmp", "ico", "tiff", "webp", "tga", "gif",
+];
 
-fn save_image(image: &egui::ColorImage, name: &str) {
-    let picked_path = if let Some(path) = rfd::FileDialog::new()
+fn pick_save_path(name: &str) -> Option<String> {
+    rfd::FileDialog::new()
         .set_file_name(name)
         .add_filter("Image Files", &IMAGE_EXTENSIONS)
         .save_file()
-    {
-        path.display().to_string()
-    } else {
-        return;
-    };
-
-    image::save_buffer(
-        &picked_path,
-        image.as_raw(),
-        image.width() as u32,
-        image.height() as u32,
-        image::ColorType::Rgba8,
-    )
-    .expect(&format!("ERROR: failed to save file {}", &picked_path));
+        .map(|path| path.display().to_string())
 }
 
 fn pick_image() -> Option<String> {
-    if let Some(path) = rfd::FileDialog::new()
+    rfd::FileDialog::new()
         .add_filter("Image Files", &IMAGE_EXTENSIONS)
         .pick_file()
-    {
-        Some(path.display().to_string())
-    } else {
-        None
+        .map(|path| path.display().to_string())
+}
+
+/// Downscales `image` so that its longer edge is at most `size` pixels.
+fn preview_proxy(image: &image::RgbaImage, size: u32) -> image::RgbaImage {
+    let long_edge = image.width().max(image.height());
+    if long_edge <= size {
+        return image.clone();
     }
+
+    let scale = size as f32 / long_edge as f32;
+    let width = ((image.width() as f32 * scale).round() as u32).max(1);
+    let height = ((image.height() as f32 * scale).round() as u32).max(1);
+    image::imageops::thumbnail(image, width, height)
+}
+
+fn sorted_copy(
+    image: &image::RgbaImage,
+    settings: &Settings,
+) -> Result<image::RgbaImage, PorterError> {
+    let mut sorted = image.clone();
+    sort_image_buffer(&mut sorted, settings)?;
+    Ok(sorted)
 }
 
+// every state is a full copy of the image, so keep only a few of them around
+const HISTORY_LIMIT: usize = 10;
+
+// how long the settings have to stay the same before a zoomed in view gets
+// sorted at full resolution
+const FULL_RESOLUTION_DELAY: Duration = Duration::from_millis(500);
+
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 16.0;
+
+// pixels stay sharp when zoomed in, so that the edges of intervals can be inspected
+const TEXTURE_OPTIONS: egui::TextureOptions = egui::TextureOptions {
+    magnification: egui::TextureFilter::Nearest,
+    minification: egui::TextureFilter::Linear,
+};
+
 fn gui_main() -> Result<(), eframe::Error> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(1024.0, 1024.0)),
+        resizable: true,
         default_theme: eframe::Theme::Light,
         follow_system_theme: true,
         ..Default::default()
     };
 
-    let mut lower_threshold: u16 = 0;
-    let mut higher_threshold: u16 = 255;
-    let mut sort_by: SortBy = SortBy::Luminance;
+    let mut settings = Settings {
+        // picked once, so that the shuffled preview doesn't flicker on every change
+        seed: Some(rand::random()),
+        ..Settings::default()
+    };
+    let mut descending = false;
     let mut texture: Option<egui::TextureHandle> = None;
-    let mut image = egui::ColorImage::new([512, 512], egui::Color32::TRANSPARENT);
-    let mut sorted_image = image.clone();
+    let mut unsorted_texture: Option<egui::TextureHandle> = None;
+    let mut show_unsorted = false;
+    let mut image = image::RgbaImage::new(512, 512);
+    // smaller copy of `image` that gets sorted on every change for the preview,
+    // the full resolution image is only sorted by "Sort" and "Save file…"
+    let mut preview_size: u32 = 1024;
+    // zoom is relative to the full resolution image, it's ignored while fitting to the window
+    let mut fit_to_window = true;
+    let mut zoom: f32 = 1.0;
+    let mut proxy = image.clone();
+    let mut proxy_stale = true;
+    // full resolution result of sorting `image`, shown instead of the preview
+    // and written by "Save file…" until something changes
+    let mut full_result: Option<image::RgbaImage> = None;
+    let mut full_texture: Option<egui::TextureHandle> = None;
+    // full resolution image from before the sort, for "Before" when zoomed in
+    let mut full_unsorted_texture: Option<egui::TextureHandle> = None;
+    let mut last_change = Instant::now();
+    // set by "Sort", `full_texture` is then of `image` itself
+    let mut committed = false;
+    // along with whether the image was shown as the result of a "Sort", so that
+    // undoing brings back what was on screen. redone images always were
+    let mut undo_history: Vec<(image::RgbaImage, bool)> = Vec::new();
+    let mut redo_history: Vec<image::RgbaImage> = Vec::new();
     let mut changed = true;
     let mut image_name = "placeholder".to_string();
     let mut error_message: Option<String> = None;
+    let mut save_status: Option<String> = None;
 
     eframe::run_simple_native("PSORTER", options, move |ctx, _frame| {
+        let (undo_pressed, redo_pressed) = ctx.input_mut(|input| {
+            let redo = input.consume_shortcut(&egui::KeyboardShortcut::new(
+                egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                egui::Key::Z,
+            ));
+            let undo = input.consume_shortcut(&egui::KeyboardShortcut::new(
+                egui::Modifiers::COMMAND,
+                egui::Key::Z,
+            ));
+            (undo, redo)
+        });
+
         egui::TopBottomPanel::top("my_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.with_layout(
                     egui::Layout::default().with_cross_align(egui::Align::LEFT),
                     |ui| {
                         ui.horizontal(|ui| {
-                            let upper_boundary = threshold_upper_boundary(&sort_by);
+                            let upper_boundary = threshold_upper_boundary(&settings.method);
 
-                            let mut new_lower_threshold = lower_threshold;
+                            let mut new_lower_threshold = settings.lower_threshold;
                             ui.label("Lower threshold: ");
                             changed = ui
                                 .add(egui::Slider::new(
@@ -293,11 +705,12 @@ fn gui_main() -> Result<(), eframe::Error> {
                                 ))
                                 .changed()
                                 || changed;
-                            lower_threshold = new_lower_threshold.clamp(0, higher_threshold);
+                            settings.lower_threshold =
+                                new_lower_threshold.clamp(0, settings.higher_threshold);
 
                             ui.separator();
 
-                            let mut new_higher_threshold = higher_threshold;
+                            let mut new_higher_threshold = settings.higher_threshold;
                             ui.label("Higher threshold: ");
                             changed = ui
                                 .add(egui::Slider::new(
@@ -306,8 +719,31 @@ fn gui_main() -> Result<(), eframe::Error> {
                                 ))
                                 .changed()
                                 || changed;
-                            higher_threshold =
-                                new_higher_threshold.clamp(lower_threshold, upper_boundary);
+                            settings.higher_threshold = new_higher_threshold
+                                .clamp(settings.lower_threshold, upper_boundary);
+
+                            ui.separator();
+
+                            ui.label("Preview quality: ");
+                            if ui
+                                .add(egui::Slider::new(&mut preview_size, 256..=4096).suffix("px"))
+                                .changed()
+                            {
+                                changed = true;
+                                proxy_stale = true;
+                            }
+
+                            ui.separator();
+
+                            ui.checkbox(&mut fit_to_window, "Fit");
+                            ui.label("Zoom: ");
+                            ui.add_enabled(
+                                !fit_to_window,
+                                egui::Slider::new(&mut zoom, MIN_ZOOM..=MAX_ZOOM)
+                                    .logarithmic(true)
+                                    .suffix("x"),
+                            )
+                            .on_hover_text("Relative to the full resolution image");
                         });
                     },
                 );
@@ -322,15 +758,145 @@ fn gui_main() -> Result<(), eframe::Error> {
                                         Ok(x) => {
                                             image = x;
                                             changed = true;
-                                            image_name = basename(&new_image_path);
+                                            proxy_stale = true;
+                                            undo_history.clear();
+                                            redo_history.clear();
+                                            image_name = basename(Path::new(&new_image_path))
+                                                .unwrap_or_else(|_| new_image_path.clone());
                                         }
                                         Err(e) => error_message = Some(e.to_string()),
                                     };
                                 }
                             }
 
+                            // sort the working image in place, so that several passes
+                            // with different settings can be stacked on top of each other
+                            if ui.button("Sort").clicked() {
+                                // sorting the result of the last sort again, which becomes
+                                // the new "Before"
+                                if committed {
+                                    proxy = preview_proxy(&image, preview_size);
+                                    unsorted_texture = Some(ctx.load_texture(
+                                        format!("unsorted-{}", image_name),
+                                        to_color_image(&proxy),
+                                        TEXTURE_OPTIONS,
+                                    ));
+                                    full_unsorted_texture = None;
+                                }
+
+                                let sorted = match full_result.take() {
+                                    Some(sorted) if !committed => Ok(sorted),
+                                    _ => sorted_copy(&image, &settings),
+                                };
+                                match sorted {
+                                    Ok(sorted) => {
+                                        let previous = std::mem::replace(&mut image, sorted);
+                                        undo_history.push((previous, committed));
+                                        if undo_history.len() > HISTORY_LIMIT {
+                                            undo_history.remove(0);
+                                        }
+                                        redo_history.clear();
+                                        // "Before" keeps showing the image from before the sort,
+                                        // the proxy is only rebuilt with the next change
+                                        full_texture = Some(ctx.load_texture(
+                                            format!("full-{}", image_name),
+                                            to_color_image(&image),
+                                            TEXTURE_OPTIONS,
+                                        ));
+                                        committed = true;
+                                        proxy_stale = true;
+                                    }
+                                    Err(e) => error_message = Some(e.to_string()),
+                                }
+                            }
+
+                            let mut commit_view: Option<bool> = None;
+                            let undo_button =
+                                ui.add_enabled(!undo_history.is_empty(), egui::Button::new("Undo"));
+                            if undo_button.clicked() || undo_pressed {
+                                if let Some((previous, was_committed)) = undo_history.pop() {
+                                    redo_history.push(std::mem::replace(&mut image, previous));
+                                    commit_view = Some(was_committed);
+                                }
+                            }
+
+                            let redo_button =
+                                ui.add_enabled(!redo_history.is_empty(), egui::Button::new("Redo"));
+                            if redo_button.clicked() || redo_pressed {
+                                if let Some(next) = redo_history.pop() {
+                                    let previous = std::mem::replace(&mut image, next);
+                                    undo_history.push((previous, committed));
+                                    commit_view = Some(true);
+                                }
+                            }
+
+                            match commit_view {
+                                // show `image` as the result of the sort that made it, with the
+                                // image it was sorted from as "Before"
+                                Some(true) => {
+                                    let before = match undo_history.last() {
+                                        Some((previous, _)) => previous,
+                                        None => &image,
+                                    };
+                                    unsorted_texture = Some(ctx.load_texture(
+                                        format!("unsorted-{}", image_name),
+                                        to_color_image(&preview_proxy(before, preview_size)),
+                                        TEXTURE_OPTIONS,
+                                    ));
+                                    full_texture = Some(ctx.load_texture(
+                                        format!("full-{}", image_name),
+                                        to_color_image(&image),
+                                        TEXTURE_OPTIONS,
+                                    ));
+                                    full_result = None;
+                                    full_unsorted_texture = None;
+                                    committed = true;
+                                    proxy_stale = true;
+                                }
+                                Some(false) => {
+                                    changed = true;
+                                    proxy_stale = true;
+                                }
+                                None => {}
+                            }
+
                             if ui.button("Save file…").clicked() {
-                                save_image(&sorted_image, &image_name);
+                                if let Some(path) =
+                                    pick_save_path(&format!("sorted-{}", image_name))
+                                {
+                                    if !committed && full_result.is_none() {
+                                        match sorted_copy(&image, &settings) {
+                                            Ok(sorted) => full_result = Some(sorted),
+                                            Err(e) => {
+                                                save_status = Some(format!("Failed to save: {}", e))
+                                            }
+                                        }
+                                    }
+
+                                    let to_save = if committed {
+                                        Some(&image)
+                                    } else {
+                                        full_result.as_ref()
+                                    };
+                                    if let Some(to_save) = to_save {
+                                        // the format is inferred from the extension of the picked path
+                                        save_status = Some(match to_save.save(&path) {
+                                            Ok(()) => format!("Saved {}", path),
+                                            Err(e) => format!("Failed to save: {}", e),
+                                        });
+                                        if full_texture.is_none() {
+                                            full_texture = Some(ctx.load_texture(
+                                                format!("full-{}", image_name),
+                                                to_color_image(to_save),
+                                                TEXTURE_OPTIONS,
+                                            ));
+                                        }
+                                    }
+                                }
+                            }
+
+                            if let Some(status) = &save_status {
+                                ui.label(status);
                             }
 
                             ui.separator();
@@ -338,24 +904,77 @@ fn gui_main() -> Result<(), eframe::Error> {
                             let luminance_button = ui.add(egui::Button::new("Luminance"));
                             let hue_button = ui.add(egui::Button::new("Hue"));
                             let saturation_button = ui.add(egui::Button::new("Saturation"));
+                            let red_button = ui.add(egui::Button::new("Red"));
+                            let green_button = ui.add(egui::Button::new("Green"));
+                            let blue_button = ui.add(egui::Button::new("Blue"));
 
-                            if luminance_button.clicked() {
-                                sort_by = SortBy::Luminance;
-                                changed = true;
+                            let clicked_method = if luminance_button.clicked() {
+                                Some(SortBy::Luminance)
                             } else if hue_button.clicked() {
-                                sort_by = SortBy::Hue;
-                                changed = true;
+                                Some(SortBy::Hue)
                             } else if saturation_button.clicked() {
-                                sort_by = SortBy::Saturation;
+                                Some(SortBy::Saturation)
+                            } else if red_button.clicked() {
+                                Some(SortBy::Red)
+                            } else if green_button.clicked() {
+                                Some(SortBy::Green)
+                            } else if blue_button.clicked() {
+                                Some(SortBy::Blue)
+                            } else {
+                                None
+                            };
+
+                            if let Some(method) = clicked_method {
+                                // hue goes up to 360, the other properties only up to 255
+                                let boundary = threshold_upper_boundary(&method);
+                                settings.method = method;
+                                settings.lower_threshold = settings.lower_threshold.min(boundary);
+                                settings.higher_threshold = settings.higher_threshold.min(boundary);
                                 changed = true;
                             }
 
-                            match sort_by {
+                            match settings.method {
                                 SortBy::Luminance => luminance_button,
                                 SortBy::Hue => hue_button,
                                 SortBy::Saturation => saturation_button,
+                                SortBy::Red => red_button,
+                                SortBy::Green => green_button,
+                                SortBy::Blue => blue_button,
                             }
                             .highlight();
+
+                            ui.separator();
+
+                            let vertical_button = ui.add(egui::Button::new("Vertical"));
+                            if vertical_button.clicked() {
+                                settings.direction = if settings.direction == Direction::Vertical {
+                                    Direction::Horizontal
+                                } else {
+                                    Direction::Vertical
+                                };
+                                changed = true;
+                            }
+
+                            if settings.direction == Direction::Vertical {
+                                vertical_button.highlight();
+                            }
+
+                            if ui.checkbox(&mut descending, "Descending").changed() {
+                                settings.order = if descending {
+                                    Order::Descending
+                                } else {
+                                    Order::Ascending
+                                };
+                                changed = true;
+                            }
+                            changed =
+                                ui.checkbox(&mut settings.invert, "Invert").changed() || changed;
+                            changed =
+                                ui.checkbox(&mut settings.shuffle, "Shuffle").changed() || changed;
+
+                            ui.separator();
+
+                            ui.checkbox(&mut show_unsorted, "Before");
                         });
                     },
                 );
@@ -363,40 +982,125 @@ fn gui_main() -> Result<(), eframe::Error> {
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            if texture.is_none() {
-                texture = Some(ctx.load_texture(&image_name, image.clone(), Default::default()));
-            }
-
             if changed {
                 changed = false;
-                sorted_image = image.clone();
-                sort_image(
-                    lower_threshold,
-                    higher_threshold,
-                    &mut sorted_image,
-                    &sort_by,
-                );
+                last_change = Instant::now();
+                full_result = None;
+                full_texture = None;
+                full_unsorted_texture = None;
+                committed = false;
+
+                if proxy_stale {
+                    proxy_stale = false;
+                    proxy = preview_proxy(&image, preview_size);
+                    unsorted_texture = Some(ctx.load_texture(
+                        format!("unsorted-{}", image_name),
+                        to_color_image(&proxy),
+                        TEXTURE_OPTIONS,
+                    ));
+                }
 
-                texture =
-                    Some(ctx.load_texture(&image_name, sorted_image.clone(), Default::default()));
+                match sorted_copy(&proxy, &settings) {
+                    Ok(sorted_proxy) => {
+                        texture = Some(ctx.load_texture(
+                            &image_name,
+                            to_color_image(&sorted_proxy),
+                            TEXTURE_OPTIONS,
+                        ));
+                    }
+                    Err(e) => error_message = Some(e.to_string()),
+                }
             }
 
-            if let Some(texture) = texture.as_ref() {
-                let available_space = ui.available_size();
-                let vertical_scale = available_space.y / (image.height() as f32);
-                let horizontal_scale = available_space.x / (image.width() as f32);
-                let scale = vertical_scale.min(horizontal_scale);
+            let available_space = ui.available_size();
+            let vertical_scale = available_space.y / (image.height() as f32);
+            let horizontal_scale = available_space.x / (image.width() as f32);
+            let fit_scale = vertical_scale.min(horizontal_scale);
+
+            // ctrl+scroll zooms, starting from whatever is shown right now
+            let zoom_delta = ctx.input(|input| input.zoom_delta());
+            if zoom_delta != 1.0 {
+                if fit_to_window {
+                    fit_to_window = false;
+                    zoom = fit_scale;
+                }
+                zoom = (zoom * zoom_delta).clamp(MIN_ZOOM, MAX_ZOOM);
+            }
+
+            let scale = if fit_to_window { fit_scale } else { zoom };
+
+            // the proxy would have to be magnified, so the full resolution images are
+            // shown instead. they're only sorted once the settings have been left
+            // alone for a while, so that dragging a slider doesn't sort the whole
+            // image on every step
+            let proxy_scale = proxy.width() as f32 / image.width() as f32;
+            let idle = last_change.elapsed();
+            if scale > proxy_scale {
+                if show_unsorted {
+                    if full_unsorted_texture.is_none() {
+                        let unsorted = match undo_history.last() {
+                            Some((previous, _)) if committed => previous,
+                            _ => &image,
+                        };
+                        full_unsorted_texture = Some(ctx.load_texture(
+                            format!("full-unsorted-{}", image_name),
+                            to_color_image(unsorted),
+                            TEXTURE_OPTIONS,
+                        ));
+                    }
+                } else if full_texture.is_none() {
+                    if idle < FULL_RESOLUTION_DELAY {
+                        ctx.request_repaint_after(FULL_RESOLUTION_DELAY - idle);
+                    } else {
+                        match sorted_copy(&image, &settings) {
+                            Ok(sorted) => {
+                                full_texture = Some(ctx.load_texture(
+                                    format!("full-{}", image_name),
+                                    to_color_image(&sorted),
+                                    TEXTURE_OPTIONS,
+                                ));
+                                full_result = Some(sorted);
+                            }
+                            Err(e) => {
+                                // try again after the delay instead of on every frame
+                                last_change = Instant::now();
+                                error_message = Some(e.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+
+            let shown_texture = if show_unsorted {
+                full_unsorted_texture
+                    .as_ref()
+                    .filter(|_| scale > proxy_scale)
+                    .or(unsorted_texture.as_ref())
+            } else {
+                full_texture.as_ref().or(texture.as_ref())
+            };
+
+            if let Some(texture) = shown_texture {
                 let image_size = egui::Vec2::new(
                     (image.width() as f32) * scale,
                     (image.height() as f32) * scale,
                 );
 
-                ui.with_layout(
-                    egui::Layout::centered_and_justified(egui::Direction::LeftToRight),
-                    |ui| {
-                        ui.image(texture, image_size);
-                    },
-                );
+                if fit_to_window {
+                    ui.with_layout(
+                        egui::Layout::centered_and_justified(egui::Direction::LeftToRight),
+                        |ui| {
+                            ui.image(texture, image_size);
+                        },
+                    );
+                } else {
+                    // the image doesn't take any input, so dragging it pans
+                    egui::ScrollArea::both()
+                        .drag_to_scroll(true)
+                        .show(ui, |ui| {
+                            ui.image(texture, image_size);
+                        });
+                }
             } else {
                 ui.spinner();
             }